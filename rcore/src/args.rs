@@ -0,0 +1,314 @@
+/*
+ * args.rs: declarative argument specs for commands, with validation and usage rendering.
+ * Copyright (C) 2019  Oddcoder
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Lesser General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Lesser General Public License for more details.
+ *
+ * You should have received a copy of the GNU Lesser General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Optional, declarative argument schemas for commands. A command that
+//! registers an [`ArgSpec`] gets its raw `&[String]` validated and parsed
+//! before `run` is ever called, and gets a usage line generated for `help`
+//! for free. Commands that don't register one keep parsing their own args,
+//! exactly as they do today.
+
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ArgType {
+    Integer,
+    Hex,
+    Str,
+    Path,
+}
+
+impl ArgType {
+    fn name(self) -> &'static str {
+        match self {
+            ArgType::Integer => "integer",
+            ArgType::Hex => "hex",
+            ArgType::Str => "string",
+            ArgType::Path => "path",
+        }
+    }
+    fn parse(self, raw: &str) -> Result<ArgValue, String> {
+        match self {
+            ArgType::Integer => raw.parse::<i64>().map(ArgValue::Int).map_err(|_| format!("`{}` is not a valid integer", raw)),
+            ArgType::Hex => {
+                let digits = raw.trim_start_matches("0x");
+                i64::from_str_radix(digits, 16).map(ArgValue::Int).map_err(|_| format!("`{}` is not a valid hex value", raw))
+            }
+            ArgType::Str | ArgType::Path => Ok(ArgValue::Str(raw.to_string())),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum ArgValue {
+    Int(i64),
+    Str(String),
+}
+
+impl ArgValue {
+    pub fn as_int(&self) -> Option<i64> {
+        match *self {
+            ArgValue::Int(n) => Some(n),
+            ArgValue::Str(_) => None,
+        }
+    }
+    pub fn as_str(&self) -> Option<&str> {
+        match *self {
+            ArgValue::Str(ref s) => Some(s),
+            ArgValue::Int(_) => None,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Positional {
+    pub name: &'static str,
+    pub ty: ArgType,
+    pub description: &'static str,
+}
+
+#[derive(Clone)]
+pub struct Flag {
+    pub name: &'static str,
+    pub long: Option<&'static str>,
+    pub short: Option<char>,
+    pub ty: Option<ArgType>,
+    pub description: &'static str,
+}
+
+#[derive(Clone, Default)]
+pub struct ArgSpec {
+    pub positionals: Vec<Positional>,
+    pub rest: Option<Positional>,
+    pub flags: Vec<Flag>,
+}
+
+impl ArgSpec {
+    pub fn new() -> Self {
+        Default::default()
+    }
+    pub fn positional(mut self, name: &'static str, ty: ArgType, description: &'static str) -> Self {
+        self.positionals.push(Positional { name, ty, description });
+        self
+    }
+    pub fn rest(mut self, name: &'static str, ty: ArgType, description: &'static str) -> Self {
+        self.rest = Some(Positional { name, ty, description });
+        self
+    }
+    pub fn flag(mut self, name: &'static str, long: Option<&'static str>, short: Option<char>, ty: Option<ArgType>, description: &'static str) -> Self {
+        self.flags.push(Flag { name, long, short, ty, description });
+        self
+    }
+    /// Renders the usage as a single line, e.g. `usage: seek [--phy] <addr>`.
+    /// The line is not wrapped to the terminal width.
+    pub fn usage(&self, command_name: &str) -> String {
+        let mut usage = format!("usage: {}", command_name);
+        for flag in &self.flags {
+            let shown = flag.long.map(|l| format!("--{}", l)).or_else(|| flag.short.map(|s| format!("-{}", s))).unwrap_or_default();
+            usage.push_str(&format!(" [{}]", shown));
+        }
+        for p in &self.positionals {
+            usage.push_str(&format!(" <{}>", p.name));
+        }
+        if let Some(ref rest) = self.rest {
+            usage.push_str(&format!(" [{}...]", rest.name));
+        }
+        usage
+    }
+    /// Renders `(name column, description)` rows for every flag and
+    /// positional, in the shape [`listing::two_column`] expects, so `help`
+    /// can print a wrapped per-argument description list alongside the
+    /// single usage line.
+    pub fn describe(&self) -> Vec<(String, String)> {
+        let mut rows = Vec::with_capacity(self.flags.len() + self.positionals.len() + 1);
+        for flag in &self.flags {
+            let mut name = flag.long.map(|l| format!("--{}", l)).unwrap_or_default();
+            if let Some(short) = flag.short {
+                if !name.is_empty() {
+                    name.push_str(", ");
+                }
+                name.push_str(&format!("-{}", short));
+            }
+            rows.push((name, flag.description.to_string()));
+        }
+        for p in &self.positionals {
+            rows.push((format!("<{}>", p.name), p.description.to_string()));
+        }
+        if let Some(ref rest) = self.rest {
+            rows.push((format!("<{}...>", rest.name), rest.description.to_string()));
+        }
+        rows
+    }
+}
+
+#[derive(Debug)]
+pub struct Args {
+    values: HashMap<&'static str, ArgValue>,
+    pub tail: Vec<ArgValue>,
+}
+
+impl Args {
+    pub fn get(&self, name: &str) -> Option<&ArgValue> {
+        self.values.get(name)
+    }
+    pub fn has_flag(&self, name: &str) -> bool {
+        self.values.contains_key(name)
+    }
+}
+
+/// Whether `token` has flag syntax at all: `--long`, or `-` followed by a
+/// non-digit (a short flag). `-5` and similar negative numbers don't count,
+/// so they fall through to ordinary positional/rest parsing instead of
+/// erroring as an unknown flag.
+fn looks_like_flag(token: &str) -> bool {
+    if token.starts_with("--") {
+        return true;
+    }
+    match token.strip_prefix('-') {
+        Some(rest) => rest.chars().next().map_or(false, |c| !c.is_ascii_digit()),
+        None => false,
+    }
+}
+
+fn match_flag<'a>(spec: &'a ArgSpec, token: &str) -> Option<&'a Flag> {
+    if let Some(long) = token.strip_prefix("--") {
+        return spec.flags.iter().find(|f| f.long == Some(long));
+    }
+    if let Some(short) = token.strip_prefix('-') {
+        if short.len() == 1 {
+            let c = short.chars().next().unwrap();
+            return spec.flags.iter().find(|f| f.short == Some(c));
+        }
+    }
+    None
+}
+
+/// An argument-parsing failure, with the index into the raw `&[String]`
+/// that caused it (`None` when the problem is a missing trailing argument,
+/// which has no token of its own to point at).
+#[derive(Debug)]
+pub struct ArgError {
+    pub message: String,
+    pub index: Option<usize>,
+}
+
+fn err(index: usize, message: String) -> ArgError {
+    ArgError { message, index: Some(index) }
+}
+
+/// Validates and parses `raw` against `spec`, or returns a human readable
+/// error describing exactly which token was the problem.
+pub fn parse(spec: &ArgSpec, raw: &[String]) -> Result<Args, ArgError> {
+    let mut values = HashMap::new();
+    let mut tail = Vec::new();
+    let mut positionals = spec.positionals.iter();
+    let mut i = 0;
+    while i < raw.len() {
+        let token = &raw[i];
+        if looks_like_flag(token) {
+            let flag = match match_flag(spec, token) {
+                Some(flag) => flag,
+                None => return Err(err(i, format!("unknown flag `{}`", token))),
+            };
+            if let Some(ty) = flag.ty {
+                i += 1;
+                let value = match raw.get(i) {
+                    Some(value) => value,
+                    None => return Err(err(i - 1, format!("flag `{}` expects a {} value", token, ty.name()))),
+                };
+                match ty.parse(value) {
+                    Ok(v) => values.insert(flag.name, v),
+                    Err(message) => return Err(err(i, message)),
+                };
+            } else {
+                values.insert(flag.name, ArgValue::Int(1));
+            }
+            i += 1;
+            continue;
+        }
+        if let Some(p) = positionals.next() {
+            match p.ty.parse(token) {
+                Ok(v) => values.insert(p.name, v),
+                Err(message) => return Err(err(i, message)),
+            };
+        } else if let Some(ref rest) = spec.rest {
+            match rest.ty.parse(token) {
+                Ok(v) => tail.push(v),
+                Err(message) => return Err(err(i, message)),
+            }
+        } else {
+            return Err(err(i, format!("unexpected argument `{}`", token)));
+        }
+        i += 1;
+    }
+    if let Some(missing) = positionals.next() {
+        return Err(ArgError { message: format!("missing required argument `{}`", missing.name), index: None });
+    }
+    Ok(Args { values, tail })
+}
+
+#[cfg(test)]
+mod test_args {
+    use super::*;
+
+    #[test]
+    fn test_positional_and_flag() {
+        let spec = ArgSpec::new().positional("addr", ArgType::Hex, "Address to operate on.").flag("phy", Some("phy"), None, None, "Use the physical address space.");
+        let args = parse(&spec, &["0x500".to_string(), "--phy".to_string()]).unwrap();
+        assert_eq!(args.get("addr").unwrap().as_int(), Some(0x500));
+        assert!(args.has_flag("phy"));
+    }
+
+    #[test]
+    fn test_missing_positional() {
+        let spec = ArgSpec::new().positional("addr", ArgType::Hex, "Address to operate on.");
+        let err = parse(&spec, &[]).unwrap_err();
+        assert_eq!(err.message, "missing required argument `addr`");
+        assert_eq!(err.index, None);
+    }
+
+    #[test]
+    fn test_unknown_flag() {
+        let spec = ArgSpec::new();
+        let err = parse(&spec, &["--bogus".to_string()]).unwrap_err();
+        assert_eq!(err.message, "unknown flag `--bogus`");
+        assert_eq!(err.index, Some(0));
+    }
+
+    #[test]
+    fn test_negative_integer_is_not_a_flag() {
+        let spec = ArgSpec::new().positional("offset", ArgType::Integer, "Offset to seek to.");
+        let args = parse(&spec, &["-5".to_string()]).unwrap();
+        assert_eq!(args.get("offset").unwrap().as_int(), Some(-5));
+    }
+
+    #[test]
+    fn test_describe_lists_flags_then_positionals_then_rest() {
+        let spec = ArgSpec::new()
+            .flag("verbose", Some("verbose"), Some('v'), None, "Be verbose.")
+            .positional("script", ArgType::Path, "Path to the script file.")
+            .rest("script_args", ArgType::Str, "Extra arguments passed to the script.");
+        let rows = spec.describe();
+        assert_eq!(
+            rows,
+            vec![
+                ("--verbose, -v".to_string(), "Be verbose.".to_string()),
+                ("<script>".to_string(), "Path to the script file.".to_string()),
+                ("<script_args...>".to_string(), "Extra arguments passed to the script.".to_string()),
+            ]
+        );
+    }
+}
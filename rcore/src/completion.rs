@@ -0,0 +1,109 @@
+/*
+ * completion.rs: prefix-based tab completion for registered command names.
+ * Copyright (C) 2019  Oddcoder
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Lesser General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Lesser General Public License for more details.
+ *
+ * You should have received a copy of the GNU Lesser General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! `rustyline` completion helper. The BK-tree in `core::Core` is built for
+//! fuzzy (edit-distance) lookup and has no efficient way to answer "what
+//! starts with this prefix", so we keep a second, much simpler index: a
+//! sorted set of every registered name, shared with `Core` through an
+//! `Rc<RefCell<_>>` so both sides stay in sync without the completer having
+//! to borrow `Core` itself (the editor that owns it is a field of `Core`).
+
+use rustyline::completion::{Completer, Pair};
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Context, Helper};
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::rc::Rc;
+
+pub type CommandNames = Rc<RefCell<BTreeSet<String>>>;
+
+pub struct CommandCompleter {
+    names: CommandNames,
+}
+
+impl CommandCompleter {
+    pub fn new(names: CommandNames) -> Self {
+        CommandCompleter { names }
+    }
+}
+
+/// Word boundary the current token starts at, so completion only replaces
+/// the command name being typed and not the whole line.
+fn word_start(line: &str, pos: usize) -> usize {
+    line[..pos].rfind(' ').map(|i| i + 1).unwrap_or(0)
+}
+
+/// Whether `start` is the first token of `line`, i.e. there's nothing but
+/// whitespace before it. Command names only make sense to complete there;
+/// anything after is an argument, which this completer has no index for.
+fn is_command_position(line: &str, start: usize) -> bool {
+    line[..start].trim().is_empty()
+}
+
+/// Plain Levenshtein distance, used only as the no-prefix-match fallback so
+/// completion can still propose close misspellings, mirroring the BK-tree's
+/// distance-2 suggestions used elsewhere for `command_not_found`.
+fn levenshtein(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        let mut prev = row[0];
+        row[0] = i;
+        for j in 1..=b.len() {
+            let cur = row[j];
+            row[j] = if a[i - 1] == b[j - 1] { prev } else { 1 + prev.min(row[j]).min(row[j - 1]) };
+            prev = cur;
+        }
+    }
+    row[b.len()]
+}
+
+impl Completer for CommandCompleter {
+    type Candidate = Pair;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let start = word_start(line, pos);
+        if !is_command_position(line, start) {
+            return Ok((start, Vec::new()));
+        }
+        let word = &line[start..pos];
+        let names = self.names.borrow();
+        let mut matches: Vec<Pair> = names
+            .range(word.to_string()..)
+            .take_while(|name| name.starts_with(word))
+            .map(|name| Pair { display: name.clone(), replacement: name.clone() })
+            .collect();
+        if matches.is_empty() && !word.is_empty() {
+            matches = names
+                .iter()
+                .filter(|name| levenshtein(name, word) <= 2)
+                .map(|name| Pair { display: name.clone(), replacement: name.clone() })
+                .collect();
+        }
+        Ok((start, matches))
+    }
+}
+
+impl Hinter for CommandCompleter {
+    type Hint = String;
+}
+impl Highlighter for CommandCompleter {}
+impl Validator for CommandCompleter {}
+impl Helper for CommandCompleter {}
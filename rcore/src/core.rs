@@ -15,42 +15,105 @@
  * along with this program.  If not, see <http://www.gnu.org/licenses/>.
  */
 
+pub(crate) mod args;
+mod completion;
+mod listing;
+mod script;
+mod snippet;
+
 use app_dirs::*;
+use args::ArgSpec;
+use completion::{CommandCompleter, CommandNames};
 use helper::*;
 use io::{LISTMAPFUNCTION, MAPFUNCTION, PRINTHEXFUNCTION, UNMAPFUNCTION};
+use listing::COMMANDSFUNCTION;
 use loc::{MODEFUNCTION, SEEKFUNCTION};
 use rio::*;
 use rtrees::bktree::SpellTree;
 use rustyline::Editor;
+use script::SOURCEFUNCTION;
+use snippet::Snippet;
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap};
 use std::io;
 use std::io::Write;
 use std::mem;
+use std::ops::Range;
 use std::path::PathBuf;
+use std::ptr;
+use std::rc::Rc;
 use writer::Writer;
 use yansi::Paint;
 
+/// Splits a raw command line into whitespace-separated tokens, keeping the
+/// byte range of each token within `line` so diagnostics can underline it.
+fn tokenize(line: &str) -> Vec<(Range<usize>, String)> {
+    let mut tokens = Vec::new();
+    let mut start = None;
+    for (i, c) in line.char_indices() {
+        if c.is_whitespace() {
+            if let Some(s) = start.take() {
+                tokens.push((s..i, line[s..i].to_string()));
+            }
+        } else if start.is_none() {
+            start = Some(i);
+        }
+    }
+    if let Some(s) = start {
+        tokens.push((s..line.len(), line[s..].to_string()));
+    }
+    tokens
+}
+
+/// Joins `command`/`args` with single spaces into a display-only line, along
+/// with the byte range each one ends up at, so [`Core::run`] can feed
+/// [`Core::dispatch`] the same `(line, ranges)` shape `run_line` builds from
+/// tokenizing real input.
+fn join_with_ranges(command: &str, args: &[String]) -> (String, Vec<Range<usize>>) {
+    let mut line = command.to_string();
+    let mut ranges = vec![0..command.len()];
+    for arg in args {
+        line.push(' ');
+        let start = line.len();
+        line.push_str(arg);
+        ranges.push(start..line.len());
+    }
+    (line, ranges)
+}
+
 pub struct Core {
     pub stdout: Writer,
     pub stderr: Writer,
     pub mode: AddrMode,
     pub io: RIO,
-    pub rl: Editor<()>,
+    pub rl: Editor<CommandCompleter>,
     loc: u64,
     app_info: AppInfo,
     commands: SpellTree<&'static CmdFunctions>,
+    registered: Vec<(&'static CmdFunctions, Vec<&'static str>)>,
+    command_names: CommandNames,
+    arg_specs: HashMap<String, ArgSpec>,
+    current_args: Option<args::Args>,
     pub color_palette: Vec<(u8, u8, u8)>,
 }
 impl Default for Core {
     fn default() -> Self {
+        let command_names: CommandNames = Rc::new(RefCell::new(BTreeSet::new()));
+        let mut rl = Editor::<CommandCompleter>::new();
+        rl.set_helper(Some(CommandCompleter::new(command_names.clone())));
         Core {
             mode: AddrMode::Phy,
             stdout: Writer::new_write(Box::new(io::stdout())),
             stderr: Writer::new_write(Box::new(io::stderr())),
             io: RIO::new(),
             loc: 0,
-            rl: Editor::<()>::new(),
+            rl,
             app_info: AppInfo { name: "rair", author: "RairDevs" },
             commands: SpellTree::new(),
+            registered: Vec::new(),
+            command_names,
+            arg_specs: HashMap::new(),
+            current_args: None,
             color_palette: Vec::new(),
         }
     }
@@ -67,6 +130,16 @@ impl Core {
         self.add_command("s", &SEEKFUNCTION);
         self.add_command("unmap", &UNMAPFUNCTION);
         self.add_command("um", &UNMAPFUNCTION);
+        self.add_command("source", &SOURCEFUNCTION);
+        self.add_command(".", &SOURCEFUNCTION);
+        let source_spec = ArgSpec::new()
+            .positional("script", args::ArgType::Path, "Path to the rair script file to run.")
+            .flag("verbose", Some("verbose"), Some('v'), None, "Print the final loc once the script finishes.")
+            .rest("script_args", args::ArgType::Str, "Extra arguments, exposed to the script as arg1, arg2, ...");
+        self.set_arg_spec("source", source_spec.clone());
+        self.set_arg_spec(".", source_spec);
+        self.add_command("commands", &COMMANDSFUNCTION);
+        self.add_command("?", &COMMANDSFUNCTION);
     }
     fn init_colors(&mut self) {
         self.color_palette.push((0x58, 0x68, 0x75));
@@ -106,12 +179,45 @@ impl Core {
         let command = command_name.to_string();
         let (exact, _) = self.commands.find(&command, 0);
         if exact.is_empty() {
+            self.command_names.borrow_mut().insert(command.clone());
             self.commands.insert(command, functionality);
+            match self.registered.iter_mut().find(|(f, _)| ptr::eq(*f, functionality)) {
+                Some((_, names)) => names.push(command_name),
+                None => self.registered.push((functionality, vec![command_name])),
+            }
         } else {
             let msg = format!("Command {} already existed.", Paint::default(command_name).bold());
             error_msg(self, "Cannot add this command.", &msg);
         }
     }
+    /// Returns every registered `CmdFunctions`, grouped with all the names
+    /// (canonical name first, then aliases) that were registered against it.
+    pub(crate) fn registered_commands(&self) -> Vec<(&'static CmdFunctions, Vec<&'static str>)> {
+        self.registered.clone()
+    }
+    /// Looks up a registered command by its exact name, without the BK-tree's
+    /// fuzzy matching. Used by the scripting engine to tell registered
+    /// commands apart from its own builtins.
+    pub(crate) fn command(&self, command_name: &str) -> Option<&'static CmdFunctions> {
+        let (exact, _) = self.commands.find(&command_name.to_string(), 0);
+        exact.first().cloned()
+    }
+
+    /// The [`args::Args`] parsed for the command currently running, if it
+    /// registered an [`ArgSpec`]. Set for the duration of `run`/`run_line`'s
+    /// call into `CmdFunctions::run`, so a command can read its validated,
+    /// typed arguments instead of re-parsing the raw `&[String]` itself.
+    pub(crate) fn parsed_args(&self) -> Option<&args::Args> {
+        self.current_args.as_ref()
+    }
+
+    /// Attaches a declarative [`ArgSpec`] to an already registered command
+    /// name, so `run`/`help` validate and document its arguments instead of
+    /// handing it the raw `&[String]` unchecked. Optional: commands that
+    /// don't call this keep parsing their own arguments exactly as before.
+    pub fn set_arg_spec(&mut self, command_name: &str, spec: ArgSpec) {
+        self.arg_specs.insert(command_name.to_string(), spec);
+    }
     fn command_not_found(&mut self, command: &str) {
         let msg = format!("Command {} is not found.", Paint::default(command).bold());
         error_msg(self, "Execution failed", &msg);
@@ -127,13 +233,69 @@ impl Core {
         }
     }
 
+    /// Runs `command` with already-split `args`. Dispatches through the same
+    /// path `run_line` uses (synthesizing a display-only line purely to have
+    /// something to underline), so every caller of `run` -- scripts, `run_at`,
+    /// and anything else in this checkout -- gets the same snippet-rendered
+    /// diagnostics a typed REPL line would, instead of a separate flat one.
     pub fn run(&mut self, command: &str, args: &[String]) {
+        let (line, ranges) = join_with_ranges(command, args);
+        self.dispatch(command, args, &ranges, &line);
+    }
+
+    /// Tokenizes a raw REPL line and dispatches it, rendering argument errors
+    /// and unknown-command errors as a [`snippet::Snippet`] underlining the
+    /// offending token in `line` rather than a flat message.
+    pub fn run_line(&mut self, line: &str) {
+        let tokens = tokenize(line);
+        if tokens.is_empty() {
+            return;
+        }
+        let command = tokens[0].1.clone();
+        let args: Vec<String> = tokens[1..].iter().map(|(_, tok)| tok.clone()).collect();
+        let ranges: Vec<Range<usize>> = tokens.iter().map(|(range, _)| range.clone()).collect();
+        self.dispatch(&command, &args, &ranges, line);
+    }
+
+    /// Shared implementation behind `run`/`run_line`: looks `command` up,
+    /// validates `args` against its [`ArgSpec`] if it registered one, and
+    /// either runs it or renders a [`snippet::Snippet`] underlining the
+    /// relevant token of `line` (using `ranges`, one per `command`+`args`
+    /// entry) to explain why it didn't.
+    fn dispatch(&mut self, command: &str, args: &[String], ranges: &[Range<usize>], line: &str) {
         let (exact, _) = self.commands.find(&command.to_string(), 2);
         if exact.is_empty() {
-            self.command_not_found(command);
-        } else {
-            (exact[0].run)(self, args)
+            self.command_not_found_in_line(command, ranges[0].clone(), line);
+            return;
+        }
+        self.current_args = None;
+        if let Some(spec) = self.arg_specs.get(command) {
+            match args::parse(spec, args) {
+                Ok(parsed) => self.current_args = Some(parsed),
+                Err(e) => {
+                    let range = match e.index {
+                        Some(idx) => ranges[idx + 1].clone(),
+                        None => line.len()..line.len(),
+                    };
+                    let snippet = Snippet::new(line, "Invalid arguments.", &e.message).error(range, &e.message).note(ranges[0].clone(), &spec.usage(command));
+                    snippet::render(self, &snippet);
+                    return;
+                }
+            }
         }
+        (exact[0].run)(self, args);
+        self.current_args = None;
+    }
+
+    fn command_not_found_in_line(&mut self, command: &str, range: Range<usize>, line: &str) {
+        let (_, similar) = self.commands.find(&command.to_string(), 2);
+        let message = format!("Command {} is not found.", command);
+        let mut snippet = Snippet::new(line, "Execution failed", &message).error(range.clone(), "command not found");
+        if !similar.is_empty() {
+            let suggestions = similar.join(", ");
+            snippet = snippet.note(range, &format!("similar: {}", suggestions));
+        }
+        snippet::render(self, &snippet);
     }
 
     pub fn run_at(&mut self, command: &str, args: &[String], at: u64) {
@@ -146,9 +308,16 @@ impl Core {
         let (exact, _) = self.commands.find(&command.to_string(), 2);
         if exact.is_empty() {
             self.command_not_found(command);
-        } else {
-            (exact[0].help)(self);
+            return;
+        }
+        if let Some(spec) = self.arg_specs.get(command).cloned() {
+            writeln!(self.stdout, "{}", spec.usage(command)).unwrap();
+            let rows = spec.describe();
+            if !rows.is_empty() {
+                listing::two_column(self, &rows);
+            }
         }
+        (exact[0].help)(self);
     }
 }
 
@@ -193,6 +362,26 @@ mod test_core {
         core.stdout = Writer::new_buf();
         core.run_at("seeker", &[], 0x500);
         assert_eq!(core.stdout.utf8_string().unwrap(), "");
-        assert_eq!(core.stderr.utf8_string().unwrap(), "Error: Execution failed\nCommand seeker is not found.\nSimilar command: seek.\n");
+        assert_eq!(core.stderr.utf8_string().unwrap(), "seeker\n^^^^^^\ncommand not found\n^^^^^^\nsimilar: seek\n");
+    }
+    #[test]
+    fn test_add_command_updates_completion_index() {
+        let mut core = Core::new();
+        assert!(core.command_names.borrow().contains("seek"));
+        assert!(core.command_names.borrow().contains("s"));
+        assert!(!core.command_names.borrow().contains("nonexistent"));
+    }
+    #[test]
+    fn test_run_line_not_found_underlines_command() {
+        Paint::disable();
+        let mut core = Core::new();
+        core.stderr = Writer::new_buf();
+        core.stdout = Writer::new_buf();
+        core.run_line("seeker 0x500");
+        assert_eq!(core.stdout.utf8_string().unwrap(), "");
+        let err = core.stderr.utf8_string().unwrap();
+        assert!(err.starts_with("seeker 0x500\n"));
+        assert!(err.contains("command not found"));
+        assert!(err.contains("similar: seek"));
     }
 }
\ No newline at end of file
@@ -0,0 +1,122 @@
+/*
+ * listing.rs: the `commands`/`?` overview and the two-column description list it renders with.
+ * Copyright (C) 2019  Oddcoder
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Lesser General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Lesser General Public License for more details.
+ *
+ * You should have received a copy of the GNU Lesser General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use core::Core;
+use helper::*;
+use std::io::Write;
+use writer::Writer;
+
+const ROOT_DESCRIPTION: &str = "rair: a reverse engineering framework.";
+const MIN_DESCRIPTION_WIDTH: usize = 20;
+
+/// Terminal width, read from the `COLUMNS` environment variable rather than
+/// pulling in a terminal-size crate, falling back to 80 when it's unset or
+/// unparsable (e.g. output isn't a terminal at all).
+fn term_width() -> usize {
+    std::env::var("COLUMNS").ok().and_then(|w| w.parse().ok()).unwrap_or(80)
+}
+
+/// Greedily wraps `text` into lines no wider than `width` (never splitting a
+/// word), returning at least one (possibly empty) line.
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut current = String::new();
+    for word in text.split_whitespace() {
+        if !current.is_empty() && current.len() + 1 + word.len() > width {
+            lines.push(current);
+            current = String::new();
+        }
+        if !current.is_empty() {
+            current.push(' ');
+        }
+        current.push_str(word);
+    }
+    if !current.is_empty() || lines.is_empty() {
+        lines.push(current);
+    }
+    lines
+}
+
+/// Renders `rows` (name column, description) as a two-column list: the name
+/// column is sized to the longest name, the description column wraps to
+/// whatever terminal width is left.
+pub(crate) fn two_column(core: &mut Core, rows: &[(String, String)]) {
+    let name_width = rows.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+    let gap = 2;
+    let desc_width = term_width().saturating_sub(name_width + gap).max(MIN_DESCRIPTION_WIDTH);
+    for (name, desc) in rows {
+        let wrapped = wrap(desc, desc_width);
+        let mut wrapped = wrapped.into_iter();
+        writeln!(core.stdout, "{:width$}{}", name, wrapped.next().unwrap_or_default(), width = name_width + gap).unwrap();
+        for line in wrapped {
+            writeln!(core.stdout, "{:width$}{}", "", line, width = name_width + gap).unwrap();
+        }
+    }
+}
+
+/// Runs `functionality.help` against a scratch buffer and pulls out its
+/// first non-usage line as a short description, since `CmdFunctions` itself
+/// carries no description field.
+fn short_description(core: &mut Core, functionality: &'static CmdFunctions) -> String {
+    let real_stdout = std::mem::replace(&mut core.stdout, Writer::new_buf());
+    (functionality.help)(core);
+    let captured = std::mem::replace(&mut core.stdout, real_stdout);
+    let text = captured.utf8_string().unwrap_or_default();
+    text.lines()
+        .map(|l| l.trim())
+        .find(|l| !l.is_empty() && !l.to_lowercase().starts_with("usage"))
+        .unwrap_or("")
+        .to_string()
+}
+
+pub(crate) fn list_commands(core: &mut Core) {
+    writeln!(core.stdout, "{}\n", ROOT_DESCRIPTION).unwrap();
+    let groups = core.registered_commands();
+    let mut rows = Vec::with_capacity(groups.len());
+    for (functionality, names) in groups {
+        let desc = short_description(core, functionality);
+        rows.push((names.join("/"), desc));
+    }
+    two_column(core, &rows);
+}
+
+fn commands_run(core: &mut Core, _args: &[String]) {
+    list_commands(core);
+}
+
+fn commands_help(core: &mut Core) {
+    let _ = writeln!(core.stdout, "Usage: commands|?");
+    let _ = writeln!(core.stdout, "\tLists every registered command, grouped with its aliases.");
+}
+
+pub static COMMANDSFUNCTION: CmdFunctions = CmdFunctions { run: commands_run, help: commands_help };
+
+#[cfg(test)]
+mod test_listing {
+    use super::*;
+
+    #[test]
+    fn test_wrap_keeps_words_whole() {
+        let lines = wrap("dump bytes at the current location", 10);
+        assert_eq!(lines, vec!["dump bytes", "at the", "current", "location"]);
+    }
+
+    #[test]
+    fn test_wrap_empty_text() {
+        assert_eq!(wrap("", 10), vec![""]);
+    }
+}
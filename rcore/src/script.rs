@@ -0,0 +1,511 @@
+/*
+ * script.rs: a tiny Starlark-like interpreter for automating command sequences.
+ * Copyright (C) 2019  Oddcoder
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Lesser General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Lesser General Public License for more details.
+ *
+ * You should have received a copy of the GNU Lesser General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Just enough of a Starlark-like language to script sequences of rair
+//! commands: assignments, `for` loops over integer lists and bare command
+//! calls. Every called name that isn't a builtin (`get_loc`/`set_loc`) is
+//! resolved against the command table that `Core` already knows about; bare
+//! identifiers are script variables (or `loc`).
+//!
+//! A `for` loop's body is either an indented block on the following lines,
+//! or an inline suite on the loop's own line: `for a in [1, 2]: seek(a); px(16)`.
+
+use core::Core;
+use helper::*;
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+
+#[derive(Clone, Debug)]
+enum Value {
+    Int(i64),
+    Str(String),
+}
+
+impl Value {
+    fn as_arg(&self) -> String {
+        match *self {
+            Value::Int(n) => format!("{}", n),
+            Value::Str(ref s) => s.clone(),
+        }
+    }
+    fn as_int(&self) -> Result<i64, String> {
+        match *self {
+            Value::Int(n) => Ok(n),
+            Value::Str(ref s) => Err(format!("expected an integer, found string `{}`", s)),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Expr {
+    Int(i64),
+    Str(String),
+    List(Vec<Expr>),
+    Ident(String),
+    Call(String, Vec<Expr>),
+}
+
+#[derive(Debug)]
+enum Stmt {
+    Assign(String, Expr),
+    For(String, Expr, Vec<Stmt>),
+    Call(Expr),
+}
+
+struct Parser<'a> {
+    lines: &'a [(usize, String)],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(lines: &'a [(usize, String)]) -> Self {
+        Parser { lines, pos: 0 }
+    }
+
+    fn peek(&self) -> Option<&(usize, String)> {
+        self.lines.get(self.pos)
+    }
+
+    /// Parses statements at exactly `indent`, stopping at the first
+    /// shallower line. `indent` is the literal count of leading whitespace
+    /// characters `tokenize_lines` measured, not a `/4`-divided "level" --
+    /// that keeps 2-space- or tab-indented bodies from silently collapsing
+    /// onto the same level as their parent.
+    fn parse_block(&mut self, indent: usize) -> Result<Vec<Stmt>, String> {
+        let mut stmts = Vec::new();
+        while let Some(&(lvl, ref line)) = self.peek() {
+            if lvl < indent {
+                break;
+            }
+            if lvl > indent {
+                return Err(format!("unexpected indentation in: {}", line));
+            }
+            self.pos += 1;
+            if let Some(head) = line.strip_prefix("for ") {
+                let (var, rest) = split_once(head, " in ").ok_or_else(|| format!("malformed for loop: {}", line))?;
+                let (iter_src, inline_body) = split_inline_suite(rest.trim())?;
+                let iter = parse_expr(iter_src)?;
+                let body = match inline_body {
+                    Some(suite) => parse_inline_stmts(&suite)?,
+                    None => self.parse_nested_block(indent)?,
+                };
+                stmts.push(Stmt::For(var.trim().to_string(), iter, body));
+            } else {
+                stmts.push(parse_simple_stmt(line)?);
+            }
+        }
+        Ok(stmts)
+    }
+
+    /// Parses the indented block nested under a statement at `parent_indent`:
+    /// the block's own indent is whatever the next line's actually is, as
+    /// long as it's deeper than `parent_indent`, rather than assuming it's
+    /// exactly one fixed step in.
+    fn parse_nested_block(&mut self, parent_indent: usize) -> Result<Vec<Stmt>, String> {
+        let body_indent = match self.peek() {
+            Some(&(lvl, _)) if lvl > parent_indent => lvl,
+            _ => return Err("expected an indented block after `for`".to_string()),
+        };
+        self.parse_block(body_indent)
+    }
+}
+
+/// Parses a single assignment or bare call statement, shared between block
+/// parsing and an inline `for ... : stmt; stmt` suite.
+fn parse_simple_stmt(line: &str) -> Result<Stmt, String> {
+    if let Some((name, expr)) = split_once(line, "=") {
+        let name = name.trim();
+        if is_ident(name) {
+            return Ok(Stmt::Assign(name.to_string(), parse_expr(expr.trim())?));
+        }
+    }
+    Ok(Stmt::Call(parse_expr(line)?))
+}
+
+/// Splits a `for`'s `<iter>: <inline suite>` tail at the first top-level
+/// `:` (ignoring any inside brackets), returning the iterable source and,
+/// if anything non-empty follows the colon, the inline suite's source.
+fn split_inline_suite(s: &str) -> Result<(&str, Option<String>), String> {
+    let mut depth = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '(' | '[' => depth += 1,
+            ')' | ']' => depth -= 1,
+            ':' if depth == 0 => {
+                let body = s[i + 1..].trim();
+                return Ok((&s[..i], if body.is_empty() { None } else { Some(body.to_string()) }));
+            }
+            _ => {}
+        }
+    }
+    Err(format!("for loop is missing a `:`: {}", s))
+}
+
+/// Splits an inline suite into its `;`-separated statements (ignoring any
+/// `;` inside brackets) and parses each one.
+fn parse_inline_stmts(src: &str) -> Result<Vec<Stmt>, String> {
+    let mut depth = 0;
+    let mut start = 0;
+    let mut parts = Vec::new();
+    for (i, c) in src.char_indices() {
+        match c {
+            '(' | '[' => depth += 1,
+            ')' | ']' => depth -= 1,
+            ';' if depth == 0 => {
+                parts.push(&src[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&src[start..]);
+    parts.into_iter().map(str::trim).filter(|s| !s.is_empty()).map(parse_simple_stmt).collect()
+}
+
+fn split_once<'a>(s: &'a str, sep: &str) -> Option<(&'a str, &'a str)> {
+    let idx = s.find(sep)?;
+    Some((&s[..idx], &s[idx + sep.len()..]))
+}
+
+fn is_ident(s: &str) -> bool {
+    !s.is_empty() && s.chars().all(|c| c.is_alphanumeric() || c == '_') && !s.chars().next().unwrap().is_numeric()
+}
+
+fn parse_expr(src: &str) -> Result<Expr, String> {
+    let src = src.trim();
+    if let Some(inner) = src.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+        let items = split_args(inner)?;
+        let mut list = Vec::new();
+        for item in items {
+            list.push(parse_expr(&item)?);
+        }
+        return Ok(Expr::List(list));
+    }
+    if src.starts_with('"') && src.ends_with('"') && src.len() >= 2 {
+        return Ok(Expr::Str(src[1..src.len() - 1].to_string()));
+    }
+    if let Some(open) = src.find('(') {
+        if src.ends_with(')') && is_ident(&src[..open]) {
+            let inner = &src[open + 1..src.len() - 1];
+            let mut args = Vec::new();
+            for item in split_args(inner)? {
+                args.push(parse_expr(&item)?);
+            }
+            return Ok(Expr::Call(src[..open].to_string(), args));
+        }
+    }
+    if let Some(n) = parse_int(src) {
+        return Ok(Expr::Int(n));
+    }
+    if is_ident(src) {
+        return Ok(Expr::Ident(src.to_string()));
+    }
+    Err(format!("cannot parse expression: {}", src))
+}
+
+fn split_args(src: &str) -> Result<Vec<String>, String> {
+    let src = src.trim();
+    if src.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut depth = 0;
+    let mut start = 0;
+    let mut parts = Vec::new();
+    for (i, c) in src.char_indices() {
+        match c {
+            '(' | '[' => depth += 1,
+            ')' | ']' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(src[start..i].trim().to_string());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(src[start..].trim().to_string());
+    Ok(parts)
+}
+
+fn parse_int(src: &str) -> Option<i64> {
+    if let Some(hex) = src.strip_prefix("0x") {
+        return i64::from_str_radix(hex, 16).ok();
+    }
+    src.parse::<i64>().ok()
+}
+
+struct Interpreter<'a> {
+    core: &'a mut Core,
+    env: HashMap<String, Value>,
+}
+
+impl<'a> Interpreter<'a> {
+    fn eval(&mut self, expr: &Expr) -> Result<Value, String> {
+        match *expr {
+            Expr::Int(n) => Ok(Value::Int(n)),
+            Expr::Str(ref s) => Ok(Value::Str(s.clone())),
+            Expr::List(_) => Err("a list cannot be used as a value".to_string()),
+            Expr::Ident(ref name) => {
+                if name == "loc" {
+                    return Ok(Value::Int(self.core.get_loc() as i64));
+                }
+                self.env.get(name).cloned().ok_or_else(|| format!("undefined variable `{}`", name))
+            }
+            Expr::Call(ref name, ref args) => self.call(name, args),
+        }
+    }
+
+    fn eval_list(&mut self, expr: &Expr) -> Result<Vec<Value>, String> {
+        match *expr {
+            Expr::List(ref items) => items.iter().map(|e| self.eval(e)).collect(),
+            _ => Err("expected a list to iterate over".to_string()),
+        }
+    }
+
+    fn call(&mut self, name: &str, args: &[Expr]) -> Result<Value, String> {
+        // Builtins only apply when no registered command shadows them.
+        if name == "get_loc" && self.core.command(name).is_none() {
+            return Ok(Value::Int(self.core.get_loc() as i64));
+        }
+        if name == "set_loc" && self.core.command(name).is_none() {
+            let loc = self.eval(args.get(0).ok_or("set_loc needs one argument")?)?.as_int()?;
+            self.core.set_loc(loc as u64);
+            return Ok(Value::Int(loc));
+        }
+        let mut rendered = Vec::with_capacity(args.len());
+        for arg in args {
+            rendered.push(self.eval(arg)?.as_arg());
+        }
+        // Dispatch through `run`, not `run_at`: `run_at` restores `loc` to
+        // whatever it was before the call once the command returns, which
+        // would silently wipe out e.g. a `seek(a)` before the next statement
+        // ever saw it. `run_script` already restores the original `loc` once
+        // the whole script is done, which is the only place that matters.
+        self.core.run(name, &rendered);
+        Ok(Value::Int(0))
+    }
+
+    fn exec(&mut self, stmts: &[Stmt]) -> Result<(), String> {
+        for stmt in stmts {
+            match *stmt {
+                Stmt::Assign(ref name, ref expr) => {
+                    let value = self.eval(expr)?;
+                    if name == "loc" {
+                        self.core.set_loc(value.as_int()? as u64);
+                    } else {
+                        self.env.insert(name.clone(), value);
+                    }
+                }
+                Stmt::For(ref var, ref iter, ref body) => {
+                    for value in self.eval_list(iter)? {
+                        self.env.insert(var.clone(), value);
+                        self.exec(body)?;
+                    }
+                }
+                Stmt::Call(ref expr) => {
+                    self.eval(expr)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Tokenizes `src` into non-empty, non-comment lines paired with their
+/// indent: the literal count of leading whitespace characters, not a
+/// fixed-width-divided level. `Parser::parse_block`/`parse_nested_block`
+/// only ever compare these counts against each other, so any consistently
+/// applied indent style (4-space, 2-space, tabs, ...) nests correctly.
+fn tokenize_lines(src: &str) -> Vec<(usize, String)> {
+    let mut out = Vec::new();
+    for raw in src.lines() {
+        let trimmed = raw.trim_start();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        let indent = raw.len() - trimmed.len();
+        out.push((indent, trimmed.trim_end().to_string()));
+    }
+    out
+}
+
+impl Core {
+    /// Parses `src` as a rair script and runs it statement by statement,
+    /// dispatching every call through the regular command table. `loc` is
+    /// restored to whatever it was before the script started once it's done,
+    /// successfully or not.
+    pub fn run_script(&mut self, src: &str) {
+        self.run_script_with_args(src, &[]);
+    }
+
+    /// Like [`Self::run_script`], but seeds the script's environment with
+    /// `script_args` as `arg1`, `arg2`, ... (numbers parsed as `Value::Int`,
+    /// everything else as `Value::Str`), so a sourced script can take
+    /// parameters the way a command takes `&[String]`.
+    pub fn run_script_with_args(&mut self, src: &str, script_args: &[String]) {
+        let lines = tokenize_lines(src);
+        let program = match Parser::new(&lines).parse_block(0) {
+            Ok(program) => program,
+            Err(msg) => {
+                error_msg(self, "Failed to parse script.", &msg);
+                return;
+            }
+        };
+        let mut env = HashMap::new();
+        for (i, arg) in script_args.iter().enumerate() {
+            let value = parse_int(arg).map(Value::Int).unwrap_or_else(|| Value::Str(arg.clone()));
+            env.insert(format!("arg{}", i + 1), value);
+        }
+        let original_loc = self.get_loc();
+        let mut interpreter = Interpreter { core: self, env };
+        if let Err(msg) = interpreter.exec(&program) {
+            error_msg(self, "Script execution failed.", &msg);
+        }
+        self.set_loc(original_loc);
+    }
+}
+
+fn source_run(core: &mut Core, raw_args: &[String]) {
+    let (path, verbose, script_args) = match core.parsed_args() {
+        Some(parsed) => {
+            let path = parsed.get("script").and_then(|v| v.as_str()).map(|s| s.to_string());
+            let verbose = parsed.has_flag("verbose");
+            let script_args: Vec<String> =
+                parsed.tail.iter().filter_map(|v| v.as_str().map(|s| s.to_string()).or_else(|| v.as_int().map(|n| n.to_string()))).collect();
+            (path, verbose, script_args)
+        }
+        None => (raw_args.first().cloned(), false, raw_args.get(1..).unwrap_or_default().to_vec()),
+    };
+    let path = match path {
+        Some(path) => path,
+        None => {
+            error_msg(core, "Cannot run script.", "Expected a path to a script file.");
+            return;
+        }
+    };
+    match fs::read_to_string(&path) {
+        Ok(src) => {
+            core.run_script_with_args(&src, &script_args);
+            if verbose {
+                let loc = core.get_loc();
+                writeln!(core.stdout, "Finished at loc = {:#x}", loc).unwrap();
+            }
+        }
+        Err(e) => {
+            let msg = format!("Failed to read {}: {}", path, e);
+            error_msg(core, "Cannot run script.", &msg);
+        }
+    }
+}
+
+fn source_help(core: &mut Core) {
+    let _ = writeln!(core.stdout, "Usage: source|. [--verbose] <script> [script_args...]");
+    let _ = writeln!(core.stdout, "\tRuns a rair script, executing one command per statement.");
+}
+
+pub static SOURCEFUNCTION: CmdFunctions = CmdFunctions { run: source_run, help: source_help };
+
+#[cfg(test)]
+mod test_script {
+    use super::*;
+    use writer::Writer;
+    use yansi::Paint;
+
+    fn test_seek_run(core: &mut Core, args: &[String]) {
+        if let Some(loc) = parse_int(&args[0]) {
+            core.set_loc(loc as u64);
+        }
+    }
+    fn test_seek_help(core: &mut Core) {
+        let _ = writeln!(core.stdout, "Usage: tseek <loc>");
+    }
+    static TESTSEEK: CmdFunctions = CmdFunctions { run: test_seek_run, help: test_seek_help };
+
+    fn test_record_run(core: &mut Core, _args: &[String]) {
+        let _ = writeln!(core.stdout, "loc={:#x}", core.get_loc());
+    }
+    fn test_record_help(core: &mut Core) {
+        let _ = writeln!(core.stdout, "Usage: trecord");
+    }
+    static TESTRECORD: CmdFunctions = CmdFunctions { run: test_record_run, help: test_record_help };
+
+    fn test_core() -> Core {
+        Paint::disable();
+        let mut core = Core::new();
+        core.stdout = Writer::new_buf();
+        core.stderr = Writer::new_buf();
+        core
+    }
+
+    #[test]
+    fn test_for_loop_dispatches_command_per_iteration() {
+        let mut core = test_core();
+        core.add_command("tseek", &TESTSEEK);
+        core.add_command("trecord", &TESTRECORD);
+        core.run_script("for a in [0x100, 0x200]:\n    tseek(a)\n    trecord()\n");
+        assert_eq!(core.stderr.utf8_string().unwrap(), "");
+        assert_eq!(core.stdout.utf8_string().unwrap(), "loc=0x100\nloc=0x200\n");
+    }
+
+    #[test]
+    fn test_inline_for_loop_suite_dispatches_per_iteration() {
+        let mut core = test_core();
+        core.add_command("tseek", &TESTSEEK);
+        core.add_command("trecord", &TESTRECORD);
+        core.run_script("for a in [0x100, 0x200]: tseek(a); trecord()\n");
+        assert_eq!(core.stderr.utf8_string().unwrap(), "");
+        assert_eq!(core.stdout.utf8_string().unwrap(), "loc=0x100\nloc=0x200\n");
+    }
+
+    #[test]
+    fn test_for_loop_body_with_two_space_indent() {
+        let mut core = test_core();
+        core.add_command("tseek", &TESTSEEK);
+        core.add_command("trecord", &TESTRECORD);
+        core.run_script("for a in [0x100, 0x200]:\n  tseek(a)\n  trecord()\n");
+        assert_eq!(core.stderr.utf8_string().unwrap(), "");
+        assert_eq!(core.stdout.utf8_string().unwrap(), "loc=0x100\nloc=0x200\n");
+    }
+
+    #[test]
+    fn test_run_script_restores_loc_when_done() {
+        let mut core = test_core();
+        core.add_command("tseek", &TESTSEEK);
+        core.set_loc(0x42);
+        core.run_script("tseek(0x999)\n");
+        assert_eq!(core.get_loc(), 0x42);
+    }
+
+    #[test]
+    fn test_registered_command_shadows_builtin() {
+        let mut core = test_core();
+        // A registered command named like a builtin wins: the script talks
+        // to it through the regular command table, not the loc builtin.
+        core.add_command("get_loc", &TESTRECORD);
+        core.run_script("get_loc()\n");
+        assert_eq!(core.stdout.utf8_string().unwrap(), "loc=0x0\n");
+    }
+
+    #[test]
+    fn test_undefined_call_funnels_through_command_not_found() {
+        let mut core = test_core();
+        core.run_script("this_is_not_a_command()\n");
+        let err = core.stderr.utf8_string().unwrap();
+        assert!(err.contains("this_is_not_a_command"));
+        assert!(err.contains("is not found"));
+    }
+}
@@ -0,0 +1,141 @@
+/*
+ * snippet.rs: render diagnostics that underline the offending span of a command line.
+ * Copyright (C) 2019  Oddcoder
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Lesser General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Lesser General Public License for more details.
+ *
+ * You should have received a copy of the GNU Lesser General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Rustc-style "underline the offending token" diagnostics, rendered against
+//! the original command line instead of a flat two-line message.
+
+use core::Core;
+use helper::*;
+use std::io::Write;
+use std::ops::Range;
+use yansi::Paint;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Error,
+    Note,
+}
+
+pub struct Annotation {
+    pub range: Range<usize>,
+    pub label: String,
+    pub level: Level,
+}
+
+pub struct Snippet {
+    pub source: String,
+    /// Flat title/message, used verbatim through `error_msg` when there's no
+    /// annotation to underline instead.
+    pub title: String,
+    pub message: String,
+    pub annotations: Vec<Annotation>,
+}
+
+impl Snippet {
+    pub fn new(source: &str, title: &str, message: &str) -> Self {
+        Snippet { source: source.to_string(), title: title.to_string(), message: message.to_string(), annotations: Vec::new() }
+    }
+    pub fn error(mut self, range: Range<usize>, label: &str) -> Self {
+        self.annotations.push(Annotation { range, label: label.to_string(), level: Level::Error });
+        self
+    }
+    pub fn note(mut self, range: Range<usize>, label: &str) -> Self {
+        self.annotations.push(Annotation { range, label: label.to_string(), level: Level::Note });
+        self
+    }
+}
+
+/// Packs annotations into the fewest rows such that no two annotations in
+/// the same row overlap (with one column of padding between them).
+fn pack_rows(annotations: &[Annotation]) -> Vec<Vec<&Annotation>> {
+    let mut order: Vec<&Annotation> = annotations.iter().collect();
+    order.sort_by_key(|a| a.range.start);
+    let mut rows: Vec<Vec<&Annotation>> = Vec::new();
+    for annotation in order {
+        let row = rows.iter_mut().find(|row: &&mut Vec<&Annotation>| match row.last() {
+            Some(last) => last.range.end < annotation.range.start,
+            None => true,
+        });
+        match row {
+            Some(row) => row.push(annotation),
+            None => rows.push(vec![annotation]),
+        }
+    }
+    rows
+}
+
+/// Converts a byte offset into `source` into a char/column count, so a
+/// multibyte token earlier on the line doesn't throw off where later
+/// annotations' carets and labels line up.
+fn char_column(source: &str, byte_offset: usize) -> usize {
+    source[..byte_offset.min(source.len())].chars().count()
+}
+
+/// Renders one row of carets, tracking the cursor as a plain visible-column
+/// count rather than `String::len()` of the output built so far, since the
+/// latter also counts bytes from earlier annotations' ANSI escapes (and, for
+/// multibyte tokens, bytes that aren't one column wide) and would throw off
+/// the column of every annotation after the first in the row.
+fn render_caret_row(core: &mut Core, row: &[&Annotation], source: &str) {
+    let mut out = String::new();
+    let mut visible_len = 0;
+    for annotation in row {
+        let start_col = char_column(source, annotation.range.start);
+        while visible_len < start_col {
+            out.push(' ');
+            visible_len += 1;
+        }
+        let (r, g, b) = match annotation.level {
+            Level::Error => core.color_palette[3],
+            Level::Note => core.color_palette[5],
+        };
+        let span_cols = source[annotation.range.clone()].chars().count().max(1);
+        let carets = "^".repeat(span_cols);
+        out.push_str(&format!("{}", Paint::rgb(r, g, b, &carets)));
+        visible_len += span_cols;
+    }
+    writeln!(core.stderr, "{}", out).unwrap();
+}
+
+/// Prints `annotation`'s label on its own line, indented to its column, so
+/// labels never have to share a row (and hence alignment) with each other.
+fn render_label_line(core: &mut Core, annotation: &Annotation, source: &str) {
+    let (r, g, b) = match annotation.level {
+        Level::Error => core.color_palette[3],
+        Level::Note => core.color_palette[5],
+    };
+    let indent = " ".repeat(char_column(source, annotation.range.start));
+    writeln!(core.stderr, "{}{}", indent, Paint::rgb(r, g, b, &annotation.label)).unwrap();
+}
+
+/// Renders `snippet` to `core.stderr`: the source line, followed by one
+/// caret row per non-overlapping group of annotations and that group's
+/// labels, each on its own line. Falls back to the flat `title`/`message`
+/// through `error_msg` when there are no annotations to point at.
+pub fn render(core: &mut Core, snippet: &Snippet) {
+    if snippet.annotations.is_empty() {
+        error_msg(core, &snippet.title, &snippet.message);
+        return;
+    }
+    writeln!(core.stderr, "{}", snippet.source).unwrap();
+    for row in pack_rows(&snippet.annotations) {
+        render_caret_row(core, &row, &snippet.source);
+        for annotation in row {
+            render_label_line(core, annotation, &snippet.source);
+        }
+    }
+}